@@ -1,14 +1,251 @@
 type Entry = u128;
 
-// TODO(max): Make a `SmallBitSet` and `LargeBitSet` and switch between them if `num_bits` fits in
-// `Entry`.
-pub struct BitSet<T: Into<usize> + Copy> {
+/// A fixed-capacity set of small non-negative indices (instruction IDs, block IDs, etc.),
+/// represented as a bitvector. This is the workhorse for dataflow analyses: gen/kill sets,
+/// available-expression sets, and so on are all naturally "does this index hold" queries plus
+/// union/intersection/difference as the meet/transfer operators.
+///
+/// Backed by [`SmallBitSet`] (a single [`Entry`], no heap allocation) when `num_bits` fits in one
+/// word, and by [`LargeBitSet`] (a `Vec<Entry>`) otherwise, so that the common case of a
+/// per-block fact set over a modest number of instructions doesn't pay for a heap allocation.
+pub enum BitSet<T: Into<usize> + Copy> {
+    Small(SmallBitSet<T>),
+    Large(LargeBitSet<T>),
+}
+
+impl<T: Into<usize> + Copy> BitSet<T> {
+    pub fn with_capacity(num_bits: usize) -> Self {
+        if num_bits <= Entry::BITS as usize {
+            BitSet::Small(SmallBitSet::with_capacity(num_bits))
+        } else {
+            BitSet::Large(LargeBitSet::with_capacity(num_bits))
+        }
+    }
+
+    /// Returns whether the value was newly inserted: true if the set did not originally contain
+    /// the bit, and false otherwise.
+    pub fn insert(&mut self, idx: T) -> bool {
+        match self {
+            BitSet::Small(s) => s.insert(idx),
+            BitSet::Large(l) => l.insert(idx),
+        }
+    }
+
+    /// Removes `idx` from the set. Returns whether it was present beforehand.
+    pub fn remove(&mut self, idx: T) -> bool {
+        match self {
+            BitSet::Small(s) => s.remove(idx),
+            BitSet::Large(l) => l.remove(idx),
+        }
+    }
+
+    pub fn get(&self, idx: T) -> bool {
+        match self {
+            BitSet::Small(s) => s.get(idx),
+            BitSet::Large(l) => l.get(idx),
+        }
+    }
+
+    /// Removes every element from the set.
+    pub fn clear(&mut self) {
+        match self {
+            BitSet::Small(s) => s.clear(),
+            BitSet::Large(l) => l.clear(),
+        }
+    }
+
+    /// Number of elements currently in the set.
+    pub fn count_ones(&self) -> u32 {
+        match self {
+            BitSet::Small(s) => s.count_ones(),
+            BitSet::Large(l) => l.count_ones(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count_ones() == 0
+    }
+
+    /// Modify `self` to only have bits set if they are also set in `other`. Returns true if `self`
+    /// was modified, and false otherwise.
+    /// `self` and `other` must have the same number of bits.
+    pub fn intersect_with(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (BitSet::Small(s), BitSet::Small(o)) => s.intersect_with(o),
+            (BitSet::Large(s), BitSet::Large(o)) => s.intersect_with(o),
+            _ => panic!("BitSet::intersect_with called on mismatched Small/Large representations"),
+        }
+    }
+
+    /// Modify `self` to additionally have every bit that's set in `other`. Returns true if `self`
+    /// was modified, and false otherwise.
+    pub fn union_with(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (BitSet::Small(s), BitSet::Small(o)) => s.union_with(o),
+            (BitSet::Large(s), BitSet::Large(o)) => s.union_with(o),
+            _ => panic!("BitSet::union_with called on mismatched Small/Large representations"),
+        }
+    }
+
+    /// Modify `self` to remove every bit that's set in `other`. Returns true if `self` was
+    /// modified, and false otherwise.
+    pub fn difference_with(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (BitSet::Small(s), BitSet::Small(o)) => s.difference_with(o),
+            (BitSet::Large(s), BitSet::Large(o)) => s.difference_with(o),
+            _ => panic!("BitSet::difference_with called on mismatched Small/Large representations"),
+        }
+    }
+
+    /// Modify `self` to hold exactly the bits set in one of `self`/`other` but not both. Returns
+    /// true if `self` was modified, and false otherwise.
+    pub fn symmetric_difference_with(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (BitSet::Small(s), BitSet::Small(o)) => s.symmetric_difference_with(o),
+            (BitSet::Large(s), BitSet::Large(o)) => s.symmetric_difference_with(o),
+            _ => panic!("BitSet::symmetric_difference_with called on mismatched Small/Large representations"),
+        }
+    }
+
+    /// Iterate over the indices currently in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        let words: &[Entry] = match self {
+            BitSet::Small(s) => std::slice::from_ref(&s.bits),
+            BitSet::Large(l) => &l.storage,
+        };
+        words.iter().enumerate().flat_map(|(word_idx, &word)| {
+            let mut remaining = word;
+            std::iter::from_fn(move || {
+                if remaining == 0 {
+                    return None;
+                }
+                // Skip straight to the next set bit instead of testing one bit at a time.
+                let bit = remaining.trailing_zeros() as usize;
+                remaining &= remaining - 1;
+                Some(word_idx * Entry::BITS as usize + bit)
+            })
+        })
+    }
+}
+
+impl<T: Into<usize> + Copy> PartialEq for BitSet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (BitSet::Small(s), BitSet::Small(o)) => s.bits == o.bits && s.num_bits == o.num_bits,
+            (BitSet::Large(s), BitSet::Large(o)) => s.storage == o.storage && s.num_bits == o.num_bits,
+            _ => false,
+        }
+    }
+}
+
+impl<T: Into<usize> + Copy> Eq for BitSet<T> {}
+
+impl<T: Into<usize> + Copy> std::fmt::Debug for BitSet<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Into<usize> + Copy> Clone for BitSet<T> {
+    fn clone(&self) -> Self {
+        match self {
+            BitSet::Small(s) => BitSet::Small(s.clone()),
+            BitSet::Large(l) => BitSet::Large(l.clone()),
+        }
+    }
+}
+
+/// Single-word bitset used when `num_bits` fits in one [`Entry`], avoiding any heap allocation.
+pub struct SmallBitSet<T: Into<usize> + Copy> {
+    bits: Entry,
+    num_bits: usize,
+    phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: Into<usize> + Copy> Clone for SmallBitSet<T> {
+    fn clone(&self) -> Self {
+        Self { bits: self.bits, num_bits: self.num_bits, phantom: std::marker::PhantomData }
+    }
+}
+
+impl<T: Into<usize> + Copy> SmallBitSet<T> {
+    pub fn with_capacity(num_bits: usize) -> Self {
+        debug_assert!(num_bits <= Entry::BITS as usize);
+        Self { bits: 0, num_bits, phantom: Default::default() }
+    }
+
+    pub fn insert(&mut self, idx: T) -> bool {
+        debug_assert!(idx.into() < self.num_bits);
+        let mask = 1 << idx.into();
+        let newly_inserted = (self.bits & mask) == 0;
+        self.bits |= mask;
+        newly_inserted
+    }
+
+    pub fn remove(&mut self, idx: T) -> bool {
+        debug_assert!(idx.into() < self.num_bits);
+        let mask = 1 << idx.into();
+        let was_present = (self.bits & mask) != 0;
+        self.bits &= !mask;
+        was_present
+    }
+
+    pub fn get(&self, idx: T) -> bool {
+        debug_assert!(idx.into() < self.num_bits);
+        (self.bits & (1 << idx.into())) != 0
+    }
+
+    pub fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    pub fn count_ones(&self) -> u32 {
+        self.bits.count_ones()
+    }
+
+    pub fn intersect_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let before = self.bits;
+        self.bits &= other.bits;
+        self.bits != before
+    }
+
+    pub fn union_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let before = self.bits;
+        self.bits |= other.bits;
+        self.bits != before
+    }
+
+    pub fn difference_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let before = self.bits;
+        self.bits &= !other.bits;
+        self.bits != before
+    }
+
+    pub fn symmetric_difference_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let before = self.bits;
+        self.bits ^= other.bits;
+        self.bits != before
+    }
+}
+
+/// Heap-backed bitset used when `num_bits` doesn't fit in a single [`Entry`].
+pub struct LargeBitSet<T: Into<usize> + Copy> {
     storage: Vec<Entry>,
     num_bits: usize,
     phantom: std::marker::PhantomData<T>,
 }
 
-impl<T: Into<usize> + Copy> BitSet<T> {
+impl<T: Into<usize> + Copy> Clone for LargeBitSet<T> {
+    fn clone(&self) -> Self {
+        Self { storage: self.storage.clone(), num_bits: self.num_bits, phantom: std::marker::PhantomData }
+    }
+}
+
+impl<T: Into<usize> + Copy> LargeBitSet<T> {
     pub fn with_capacity(num_bits: usize) -> Self {
         // +1 because we are rounding down
         let num_entries = num_bits / (Entry::BITS as usize) + 1;
@@ -26,6 +263,15 @@ impl<T: Into<usize> + Copy> BitSet<T> {
         newly_inserted
     }
 
+    pub fn remove(&mut self, idx: T) -> bool {
+        debug_assert!(idx.into() < self.num_bits);
+        let entry_idx = idx.into() / (Entry::BITS as usize);
+        let bit_idx = idx.into() % (Entry::BITS as usize);
+        let was_present = (self.storage[entry_idx] & (1 << bit_idx)) != 0;
+        self.storage[entry_idx] &= !(1 << bit_idx);
+        was_present
+    }
+
     pub fn get(&self, idx: T) -> bool {
         debug_assert!(idx.into() < self.num_bits);
         let entry_idx = idx.into() / (Entry::BITS as usize);
@@ -33,6 +279,16 @@ impl<T: Into<usize> + Copy> BitSet<T> {
         (self.storage[entry_idx] & (1 << bit_idx)) != 0
     }
 
+    pub fn clear(&mut self) {
+        for entry in self.storage.iter_mut() {
+            *entry = 0;
+        }
+    }
+
+    pub fn count_ones(&self) -> u32 {
+        self.storage.iter().map(|e| e.count_ones()).sum()
+    }
+
     /// Modify `self` to only have bits set if they are also set in `other`. Returns true if `self`
     /// was modified, and false otherwise.
     /// `self` and `other` must have the same number of bits.
@@ -46,6 +302,39 @@ impl<T: Into<usize> + Copy> BitSet<T> {
         }
         changed
     }
+
+    pub fn union_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let mut changed = false;
+        for i in 0..self.storage.len() {
+            let before = self.storage[i];
+            self.storage[i] |= other.storage[i];
+            changed |= self.storage[i] != before;
+        }
+        changed
+    }
+
+    pub fn difference_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let mut changed = false;
+        for i in 0..self.storage.len() {
+            let before = self.storage[i];
+            self.storage[i] &= !other.storage[i];
+            changed |= self.storage[i] != before;
+        }
+        changed
+    }
+
+    pub fn symmetric_difference_with(&mut self, other: &Self) -> bool {
+        assert_eq!(self.num_bits, other.num_bits);
+        let mut changed = false;
+        for i in 0..self.storage.len() {
+            let before = self.storage[i];
+            self.storage[i] ^= other.storage[i];
+            changed |= self.storage[i] != before;
+        }
+        changed
+    }
 }
 
 #[cfg(test)]
@@ -103,4 +392,146 @@ mod tests {
         assert_eq!(left.get(1usize), true);
         assert_eq!(left.get(2usize), false);
     }
+
+    #[test]
+    fn remove_clears_bit_and_reports_prior_state() {
+        let mut set = BitSet::with_capacity(4);
+        assert_eq!(set.remove(1usize), false);
+        set.insert(1usize);
+        assert_eq!(set.remove(1usize), true);
+        assert_eq!(set.get(1usize), false);
+    }
+
+    #[test]
+    fn clear_empties_the_set() {
+        let mut set = BitSet::with_capacity(4);
+        set.insert(0usize);
+        set.insert(3usize);
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.get(0usize), false);
+        assert_eq!(set.get(3usize), false);
+    }
+
+    #[test]
+    fn count_ones_and_is_empty() {
+        let mut set = BitSet::with_capacity(8);
+        assert!(set.is_empty());
+        assert_eq!(set.count_ones(), 0);
+        set.insert(0usize);
+        set.insert(5usize);
+        assert!(!set.is_empty());
+        assert_eq!(set.count_ones(), 2);
+    }
+
+    #[test]
+    fn union_with_combines_bits_and_reports_change() {
+        let mut left = BitSet::with_capacity(4);
+        let mut right = BitSet::with_capacity(4);
+        left.insert(0usize);
+        right.insert(1usize);
+        assert_eq!(left.union_with(&right), true);
+        assert_eq!(left.get(0usize), true);
+        assert_eq!(left.get(1usize), true);
+        // No further change once already unioned.
+        assert_eq!(left.union_with(&right), false);
+    }
+
+    #[test]
+    fn difference_with_removes_bits_present_in_other() {
+        let mut left = BitSet::with_capacity(4);
+        let mut right = BitSet::with_capacity(4);
+        left.insert(0usize);
+        left.insert(1usize);
+        right.insert(1usize);
+        assert_eq!(left.difference_with(&right), true);
+        assert_eq!(left.get(0usize), true);
+        assert_eq!(left.get(1usize), false);
+    }
+
+    #[test]
+    fn symmetric_difference_with_keeps_bits_in_exactly_one_set() {
+        let mut left = BitSet::with_capacity(4);
+        let mut right = BitSet::with_capacity(4);
+        left.insert(0usize);
+        left.insert(1usize);
+        right.insert(1usize);
+        right.insert(2usize);
+        assert_eq!(left.symmetric_difference_with(&right), true);
+        assert_eq!(left.get(0usize), true);
+        assert_eq!(left.get(1usize), false);
+        assert_eq!(left.get(2usize), true);
+    }
+
+    #[test]
+    fn iter_yields_indices_in_ascending_order() {
+        let mut set = BitSet::with_capacity(8);
+        set.insert(5usize);
+        set.insert(1usize);
+        set.insert(7usize);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![1, 5, 7]);
+    }
+
+    #[test]
+    fn iter_empty_set_yields_nothing() {
+        let set: BitSet<usize> = BitSet::with_capacity(8);
+        assert_eq!(set.iter().collect::<Vec<_>>(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn equality_compares_contents() {
+        let mut left = BitSet::with_capacity(4);
+        let mut right = BitSet::with_capacity(4);
+        assert_eq!(left, right);
+        left.insert(2usize);
+        assert_ne!(left, right);
+        right.insert(2usize);
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn clone_is_independent_of_original() {
+        let mut set = BitSet::with_capacity(4);
+        set.insert(1usize);
+        let mut cloned = set.clone();
+        cloned.insert(2usize);
+        assert_eq!(set.get(2usize), false);
+        assert_eq!(cloned.get(2usize), true);
+    }
+
+    #[test]
+    fn large_bitset_beyond_one_word_round_trips() {
+        let mut set = BitSet::with_capacity(200);
+        set.insert(0usize);
+        set.insert(130usize);
+        set.insert(199usize);
+        assert_eq!(set.get(0usize), true);
+        assert_eq!(set.get(130usize), true);
+        assert_eq!(set.get(199usize), true);
+        assert_eq!(set.get(1usize), false);
+        assert_eq!(set.count_ones(), 3);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 130, 199]);
+    }
+
+    #[test]
+    fn large_bitset_union_intersect_difference() {
+        let mut left = BitSet::with_capacity(200);
+        let mut right = BitSet::with_capacity(200);
+        left.insert(0usize);
+        left.insert(199usize);
+        right.insert(199usize);
+        right.insert(130usize);
+
+        let mut union = left.clone();
+        union.union_with(&right);
+        assert_eq!(union.iter().collect::<Vec<_>>(), vec![0, 130, 199]);
+
+        let mut intersection = left.clone();
+        intersection.intersect_with(&right);
+        assert_eq!(intersection.iter().collect::<Vec<_>>(), vec![199]);
+
+        let mut difference = left.clone();
+        difference.difference_with(&right);
+        assert_eq!(difference.iter().collect::<Vec<_>>(), vec![0]);
+    }
 }
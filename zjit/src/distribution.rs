@@ -1,32 +1,75 @@
+/// A fixed-size heavy-hitter estimator based on the Space-Saving algorithm (Metwally et al.,
+/// "Efficient Computation of Frequent and Top-k Elements in Data Streams"). Unlike a naive
+/// fixed-bucket histogram, which silently drops every observation past the first `N` distinct
+/// values into a catch-all bucket, Space-Saving always keeps the `N` currently-heaviest items,
+/// evicting the lightest one to make room for a newcomer and tracking how much error that
+/// eviction may have introduced. This gives callers (e.g. receiver-class profiling) a
+/// bounded-memory estimate of the most frequent value even when the stream has more than `N`
+/// distinct values, rather than an answer that can go stale once the buckets fill up.
 #[derive(Debug, Clone)]
-pub struct Distribution<T: Copy + PartialEq + Default, const N: usize> {
-    /// buckets and counts have the same length
-    buckets: [T; N],
+pub struct Distribution<T: Copy + PartialEq, const N: usize> {
+    /// Slots currently tracked. `None` means the slot is unused.
+    buckets: [Option<T>; N],
+    /// Estimated count for the item in the corresponding bucket.
     counts: [usize; N],
-    /// if there is no more room, increment the fallback
-    other: usize,
+    /// Overestimation bound for the corresponding bucket: the count the evicted item had at the
+    /// moment this bucket's current occupant replaced it (0 if the occupant has never been
+    /// evicted into this slot).
+    errors: [usize; N],
 }
 
-impl<T: Copy + PartialEq + Default, const N: usize> Distribution<T, N> {
+impl<T: Copy + PartialEq, const N: usize> Distribution<T, N> {
     pub fn new() -> Self {
-        Self { buckets: [Default::default(); N], counts: [0; N], other: 0 }
+        Self { buckets: [None; N], counts: [0; N], errors: [0; N] }
     }
 
+    /// Record one observation of `item`, maintaining the Space-Saving invariant that the `N`
+    /// tracked slots are always the heaviest-known candidates.
     pub fn observe(&mut self, item: T) {
         assert_eq!(self.buckets.len(), self.counts.len());
+        // Item already tracked: just bump its count.
         for (bucket, count) in self.buckets.iter_mut().zip(self.counts.iter_mut()) {
-            // TODO(max): Bubble up
-            if *bucket == item {
+            if *bucket == Some(item) {
                 *count += 1;
                 return;
             }
         }
-        self.other += 1;
+        // A free slot: install the item with no accumulated error.
+        for ((bucket, count), error) in self.buckets.iter_mut().zip(self.counts.iter_mut()).zip(self.errors.iter_mut()) {
+            if bucket.is_none() {
+                *bucket = Some(item);
+                *count = 1;
+                *error = 0;
+                return;
+            }
+        }
+        // No room: evict the minimum-count slot, carrying its count forward as the error bound
+        // on the incoming item (it may have occurred that many times before we started tracking
+        // it).
+        if let Some(min_idx) = (0..N).min_by_key(|&i| self.counts[i]) {
+            let min_count = self.counts[min_idx];
+            self.buckets[min_idx] = Some(item);
+            self.counts[min_idx] = min_count + 1;
+            self.errors[min_idx] = min_count;
+        }
     }
 
+    /// Return the item with the highest estimated count, if any have been observed.
     pub fn most_common(&self) -> Option<T> {
-        // TODO(max): Return None if other count is >= sum of all other counts?
-        self.buckets.iter().zip(self.counts.iter()).max_by(|l, r| l.1.cmp(&r.1)).map(|e| e.0).copied()
+        self.buckets.iter().zip(self.counts.iter()).filter_map(|(b, c)| b.map(|b| (b, c))).max_by_key(|(_, c)| *c).map(|(b, _)| b)
+    }
+
+    /// Return the item with the highest estimated count along with whether it's a *guaranteed*
+    /// heavy hitter, i.e. `count - error > total / N`. If this returns `true`, the item's true
+    /// count provably exceeds `total / N`; this does not rule out another item having a higher
+    /// true count. If `false`, the count may be overestimated and the item may not be a heavy
+    /// hitter at all.
+    pub fn guaranteed_most_common(&self) -> Option<(T, bool)> {
+        let total: usize = self.counts.iter().sum();
+        self.buckets.iter().zip(self.counts.iter()).zip(self.errors.iter())
+            .filter_map(|((b, c), e)| b.map(|b| (b, c, e)))
+            .max_by_key(|(_, c, _)| *c)
+            .map(|(b, c, e)| (b, c.saturating_sub(*e) * N > total))
     }
 }
 
@@ -37,20 +80,17 @@ mod distribution_tests {
     #[test]
     fn start_empty() {
         let dist = Distribution::<usize, 4>::new();
-        assert!(dist.buckets.is_empty());
-        assert!(dist.counts.is_empty());
-        assert_eq!(dist.other, 0);
+        assert!(dist.buckets.iter().all(|b| b.is_none()));
+        assert_eq!(dist.counts, [0; 4]);
     }
 
     #[test]
     fn observe_adds_record() {
         let mut dist = Distribution::<usize, 4>::new();
         dist.observe(10);
-        assert_eq!(dist.buckets.len(), 1);
-        assert_eq!(dist.counts.len(), 1);
-        assert_eq!(dist.buckets[0], 10);
+        assert_eq!(dist.buckets[0], Some(10));
         assert_eq!(dist.counts[0], 1);
-        assert_eq!(dist.other, 0);
+        assert_eq!(dist.errors[0], 0);
     }
 
     #[test]
@@ -58,11 +98,8 @@ mod distribution_tests {
         let mut dist = Distribution::<usize, 4>::new();
         dist.observe(10);
         dist.observe(10);
-        assert_eq!(dist.buckets.len(), 1);
-        assert_eq!(dist.counts.len(), 1);
-        assert_eq!(dist.buckets[0], 10);
+        assert_eq!(dist.buckets[0], Some(10));
         assert_eq!(dist.counts[0], 2);
-        assert_eq!(dist.other, 0);
     }
 
     #[test]
@@ -73,22 +110,17 @@ mod distribution_tests {
         dist.observe(11);
         dist.observe(11);
         dist.observe(11);
-        assert_eq!(dist.buckets.len(), 2);
-        assert_eq!(dist.counts.len(), 2);
-        assert_eq!(dist.buckets[0], 10);
+        assert_eq!(dist.buckets[0], Some(10));
         assert_eq!(dist.counts[0], 2);
-        assert_eq!(dist.buckets[1], 11);
+        assert_eq!(dist.buckets[1], Some(11));
         assert_eq!(dist.counts[1], 3);
-        assert_eq!(dist.other, 0);
     }
 
     #[test]
-    fn observe_with_max_increments_other() {
+    fn observe_with_zero_buckets_is_noop() {
         let mut dist = Distribution::<usize, 0>::new();
         dist.observe(10);
-        assert!(dist.buckets.is_empty());
-        assert!(dist.counts.is_empty());
-        assert_eq!(dist.other, 1);
+        assert_eq!(dist.most_common(), None);
     }
 
     #[test]
@@ -97,13 +129,6 @@ mod distribution_tests {
         assert_eq!(dist.most_common(), None);
     }
 
-    #[test]
-    fn most_common_only_other() {
-        let mut dist = Distribution::<usize, 0>::new();
-        dist.observe(10);
-        assert_eq!(dist.most_common(), None);
-    }
-
     #[test]
     fn most_common() {
         let mut dist = Distribution::<usize, 4>::new();
@@ -116,4 +141,43 @@ mod distribution_tests {
         dist.observe(12);
         assert_eq!(dist.most_common(), Some(11));
     }
+
+    #[test]
+    fn eviction_replaces_minimum_bucket_and_tracks_error() {
+        let mut dist = Distribution::<usize, 2>::new();
+        dist.observe(1);
+        dist.observe(1);
+        dist.observe(1);
+        dist.observe(2);
+        // Buckets full: {1: 3, 2: 1}. Observing a brand-new value 3 evicts the minimum (2, count
+        // 1), and 3 inherits that count as its error bound.
+        dist.observe(3);
+        assert_eq!(dist.buckets[1], Some(3));
+        assert_eq!(dist.counts[1], 2);
+        assert_eq!(dist.errors[1], 1);
+        assert_eq!(dist.most_common(), Some(1));
+    }
+
+    #[test]
+    fn default_value_does_not_alias_empty_slot() {
+        // Regression test: a real T equal to T::default() must not be confused with an unused
+        // slot now that slots are tracked with Option<T>.
+        let mut dist = Distribution::<usize, 4>::new();
+        dist.observe(0);
+        dist.observe(0);
+        assert_eq!(dist.buckets[0], Some(0));
+        assert_eq!(dist.counts[0], 2);
+        assert_eq!(dist.most_common(), Some(0));
+    }
+
+    #[test]
+    fn guaranteed_most_common_is_exact_when_buckets_never_evicted() {
+        let mut dist = Distribution::<usize, 4>::new();
+        dist.observe(10);
+        dist.observe(10);
+        dist.observe(11);
+        let (val, guaranteed) = dist.guaranteed_most_common().unwrap();
+        assert_eq!(val, 10);
+        assert!(guaranteed);
+    }
 }
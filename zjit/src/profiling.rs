@@ -0,0 +1,54 @@
+//! Receiver-class profiling for polymorphic call sites.
+//!
+//! The intent: while interpreting, record the class of `self` seen at each send whose callee
+//! isn't known statically, so the HIR lowering pass can later consult the profile to speculatively
+//! specialize the dispatch to whichever class showed up most, guarding the assumption and falling
+//! back to a fully generic send if it doesn't hold (see
+//! [`crate::cruby_methods::speculative_inline_send`] for that half).
+//!
+//! This module is the data structure only: nothing in this tree calls [`CallSiteProfiles::record`]
+//! from the interpreter yet, and nothing calls [`CallSiteProfiles::dominant_class`] except
+//! `speculative_inline_send`, which itself has no caller from HIR send-lowering. Both wiring steps
+//! need files (the interpreter loop, the send-lowering pass) that don't exist in this tree.
+
+use std::collections::HashMap;
+use crate::cruby::VALUE;
+use crate::distribution::Distribution;
+
+/// Number of distinct receiver classes tracked per call site before the Space-Saving estimator
+/// starts evicting the least-common one. Polymorphic call sites are rarely more than a couple of
+/// classes wide in practice, so a small fixed width keeps the profile cheap to carry around.
+const CLASSES_PER_CALL_SITE: usize = 4;
+
+/// Identifies a send instruction by the ISEQ it appears in and its index within that ISEQ's
+/// bytecode, so that profile data collected during interpretation can be looked back up during
+/// HIR lowering of the same call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallSiteId {
+    pub iseq: crate::cruby::IseqPtr,
+    pub pc: usize,
+}
+
+/// Tracks observed receiver classes for every polymorphic call site we've interpreted.
+#[derive(Default)]
+pub struct CallSiteProfiles {
+    sites: HashMap<CallSiteId, Distribution<VALUE, CLASSES_PER_CALL_SITE>>,
+}
+
+impl CallSiteProfiles {
+    pub fn new() -> Self {
+        Self { sites: HashMap::new() }
+    }
+
+    /// Record that `site` dispatched to an object whose class is `receiver_class`.
+    pub fn record(&mut self, site: CallSiteId, receiver_class: VALUE) {
+        self.sites.entry(site).or_insert_with(Distribution::new).observe(receiver_class);
+    }
+
+    /// Return the most commonly observed receiver class at `site`, if any observations have been
+    /// made. This is a profiling hint, not a guarantee: callers must still guard it with a
+    /// `PatchPoint`/class check before relying on it for correctness.
+    pub fn dominant_class(&self, site: CallSiteId) -> Option<VALUE> {
+        self.sites.get(&site)?.most_common()
+    }
+}
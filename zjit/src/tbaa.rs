@@ -8,8 +8,9 @@
 //! an Array vs writing to a Hash) cannot alias each other, allowing the compiler
 //! to reorder or eliminate redundant memory operations.
 
-use crate::hir::{Insn, InsnId, Function};
+use crate::hir::{Insn, InsnId, BlockId, Function};
 use crate::hir_type::{Type, types};
+use crate::bitset::BitSet;
 use std::collections::HashMap;
 
 /// Alias classes represent categories of memory locations that may alias.
@@ -26,8 +27,6 @@ pub enum AliasClass {
     IntegerIvar,
     /// Instance variables of Float objects
     FloatIvar,
-    /// Instance variables of Symbol objects
-    SymbolIvar,
     /// Instance variables of Range objects
     RangeIvar,
     /// Instance variables of Regexp objects
@@ -38,6 +37,14 @@ pub enum AliasClass {
     GlobalVar,
     /// Local variables on the heap or in parent scopes
     LocalVar,
+    /// Elements (contents) of an Array object, as opposed to its ivars
+    ArrayElem,
+    /// Elements (values) of a Hash object, as opposed to its ivars
+    HashElem,
+    /// Read-only memory: the object backing this location is provably frozen or otherwise
+    /// constant, so nothing ever stores to it. Loads from an `Immutable` location always forward
+    /// to each other and are never clobbered by any store.
+    Immutable,
     /// Unknown or mixed type - may alias with anything
     Unknown,
 }
@@ -47,6 +54,9 @@ impl AliasClass {
     /// Returns true if the two classes might refer to the same memory location.
     pub fn may_alias(&self, other: &AliasClass) -> bool {
         match (self, other) {
+            // Nothing ever stores to read-only memory, so it never aliases anything -- not even
+            // another `Immutable` location, since a store can't be lurking behind either side.
+            (AliasClass::Immutable, _) | (_, AliasClass::Immutable) => false,
             // Same alias class always aliases
             (a, b) if a == b => true,
             // Unknown aliases with everything
@@ -58,8 +68,14 @@ impl AliasClass {
 
     /// Get the alias class for an instance variable access based on the object's type.
     pub fn from_ivar_type(obj_type: &Type) -> AliasClass {
-        // Use is_subtype to check what type the object could be
-        if obj_type.is_subtype(types::Array) {
+        // TODO(max): Once the HIR type lattice can answer "is this object frozen" in general
+        // (e.g. a frozen String or an interned constant), route it through `Immutable` here too,
+        // instead of only special-casing the types that are always frozen.
+        if obj_type.is_subtype(types::Symbol) {
+            // Symbols are always frozen, so a read of one of their (rare) ivars can never be
+            // invalidated by a store -- nothing ever writes to frozen memory.
+            AliasClass::Immutable
+        } else if obj_type.is_subtype(types::Array) {
             AliasClass::ArrayIvar
         } else if obj_type.is_subtype(types::Hash) {
             AliasClass::HashIvar
@@ -71,8 +87,6 @@ impl AliasClass {
             AliasClass::IntegerIvar
         } else if obj_type.is_subtype(types::Float) {
             AliasClass::FloatIvar
-        } else if obj_type.is_subtype(types::Symbol) {
-            AliasClass::SymbolIvar
         } else if obj_type.is_subtype(types::Range) {
             AliasClass::RangeIvar
         } else if obj_type.is_subtype(types::Regexp) {
@@ -87,6 +101,19 @@ impl AliasClass {
     }
 }
 
+/// A constant Hash key we can reason about precisely. Anything else (a non-constant expression,
+/// or a key type we don't special-case) falls back to `None` in `MemoryLocation::HashElement`,
+/// which is treated as may-alias-everything.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    /// A literal Symbol key, identified by its interned ID.
+    Symbol(u64),
+    /// A literal small-integer key.
+    Fixnum(i64),
+    /// A literal frozen String key, compared by contents (frozen strings are deduped by value).
+    FrozenString(String),
+}
+
 /// Represents a memory location that can be loaded from or stored to.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MemoryLocation {
@@ -96,6 +123,12 @@ pub enum MemoryLocation {
     GlobalVariable(u64),
     /// Local variable access: (level, ep_offset)
     LocalVariable(u32, u32),
+    /// Array element access: (array_id, constant_index_if_known, alias_class). A `None` index
+    /// means the index isn't a known constant at compile time.
+    ArrayElement(InsnId, Option<i64>, AliasClass),
+    /// Hash element access: (hash_id, constant_key_if_known). A `None` key means the key isn't a
+    /// constant we can compare by value.
+    HashElement(InsnId, Option<HashKey>),
 }
 
 impl MemoryLocation {
@@ -104,7 +137,7 @@ impl MemoryLocation {
         match (self, other) {
             // Same exact location always aliases
             (a, b) if a == b => true,
-            
+
             // Instance variables: check object and alias class
             (MemoryLocation::InstanceVariable(obj1, id1, class1),
              MemoryLocation::InstanceVariable(obj2, id2, class2)) => {
@@ -126,7 +159,39 @@ impl MemoryLocation {
             (MemoryLocation::LocalVariable(l1, o1), MemoryLocation::LocalVariable(l2, o2)) => {
                 l1 == l2 && o1 == o2
             }
-            
+
+            // Array elements: distinct *non-negative* constant indices into the *same* array
+            // provably don't alias; anything else (unknown index, or a different array entirely)
+            // conservatively may alias, with different arrays falling back to alias-class
+            // comparison the same way ivars do. Negative indices are excluded from the
+            // provably-distinct path because Ruby resolves them against the array's length at
+            // access time (`a[-1]` is `a[a.length - 1]`), which isn't tracked here, so e.g. `a[-1]`
+            // and `a[3]` could name the identical element depending on `a`'s length.
+            (MemoryLocation::ArrayElement(arr1, idx1, class1),
+             MemoryLocation::ArrayElement(arr2, idx2, class2)) => {
+                if arr1 != arr2 {
+                    class1.may_alias(class2)
+                } else {
+                    match (idx1, idx2) {
+                        (Some(i1), Some(i2)) if *i1 >= 0 && *i2 >= 0 => i1 == i2,
+                        _ => true,
+                    }
+                }
+            }
+
+            // Hash elements: distinct constant literal keys into the same hash don't alias;
+            // anything else is conservatively may-alias.
+            (MemoryLocation::HashElement(hash1, key1), MemoryLocation::HashElement(hash2, key2)) => {
+                if hash1 != hash2 {
+                    true
+                } else {
+                    match (key1, key2) {
+                        (Some(k1), Some(k2)) => k1 == k2,
+                        _ => true,
+                    }
+                }
+            }
+
             // Different location types don't alias
             _ => false,
         }
@@ -214,7 +279,39 @@ impl MemoryOpTracker {
                     MemoryLocation::LocalVariable(*level, *ep_offset)
                 );
             }
-            
+
+            Insn::GetArrayElement { array, idx, .. } => {
+                let index = func.known_fixnum_value(*idx);
+                self.locations.insert(
+                    insn_id,
+                    MemoryLocation::ArrayElement(*array, index, AliasClass::ArrayElem)
+                );
+            }
+
+            Insn::SetArrayElement { array, idx, .. } => {
+                let index = func.known_fixnum_value(*idx);
+                self.locations.insert(
+                    insn_id,
+                    MemoryLocation::ArrayElement(*array, index, AliasClass::ArrayElem)
+                );
+            }
+
+            Insn::GetHashElement { hash, key, .. } => {
+                let key = func.known_hash_key(*key);
+                self.locations.insert(
+                    insn_id,
+                    MemoryLocation::HashElement(*hash, key)
+                );
+            }
+
+            Insn::SetHashElement { hash, key, .. } => {
+                let key = func.known_hash_key(*key);
+                self.locations.insert(
+                    insn_id,
+                    MemoryLocation::HashElement(*hash, key)
+                );
+            }
+
             _ => {}
         }
     }
@@ -232,8 +329,211 @@ impl MemoryOpTracker {
             _ => true,
         }
     }
+
+    /// If `insn` is a store, return the value it writes.
+    fn stored_value(insn: &Insn) -> Option<InsnId> {
+        match insn {
+            Insn::SetIvar { val, .. } => Some(*val),
+            Insn::SetGlobal { val, .. } => Some(*val),
+            Insn::SetLocal { val, .. } => Some(*val),
+            Insn::SetArrayElement { val, .. } => Some(*val),
+            Insn::SetHashElement { val, .. } => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Is `insn` a load (as opposed to a store) of a tracked memory location?
+    fn is_load(insn: &Insn) -> bool {
+        matches!(insn, Insn::GetIvar { .. } | Insn::GetGlobal { .. } | Insn::GetLocal { .. }
+            | Insn::GetArrayElement { .. } | Insn::GetHashElement { .. })
+    }
+
+    /// Run redundant-load elimination and store-to-load forwarding over `func`, one basic block
+    /// at a time, using the "last store" coloring approach: within a block, track the SSA value
+    /// most recently stored to each exactly-known `MemoryLocation`, plus a cache of loads seen
+    /// since their location was last (possibly) clobbered. Before walking blocks, this also runs
+    /// [`AvailableLoads`] over the whole function and uses its per-block entry sets to seed each
+    /// block's load cache, so a load can be eliminated as redundant with one computed in a
+    /// *predecessor* block and not only with an earlier one in the same block.
+    ///
+    /// - A store to location `L` records `last_value[L]` and invalidates every `last_value`/
+    ///   cached-load entry whose location may-alias `L` (conservative clobber); anything that
+    ///   doesn't may-alias `L` is provably unaffected and stays live.
+    /// - A load of `L` first checks `last_value` for an *exactly equal* location (store-to-load
+    ///   forwarding), then the load cache for an exactly-equal prior load not yet invalidated
+    ///   (redundant-load elimination), and otherwise caches itself for later loads to reuse.
+    ///
+    /// Exact equality is what authorizes forwarding/elimination; `may_alias` is only used to decide
+    /// what a store can safely leave untouched.
+    pub fn forward_and_eliminate(func: &mut Function) {
+        let mut tracker = MemoryOpTracker::new();
+        tracker.analyze(func);
+        let available = AvailableLoads::compute(func, &tracker);
+
+        for block_id in func.rpo() {
+            let mut last_value: HashMap<MemoryLocation, InsnId> = HashMap::new();
+            let mut cached_loads: HashMap<MemoryLocation, InsnId> = HashMap::new();
+
+            // Seed the load cache from whatever's available on every path into this block. Each
+            // set bit names an earlier load instruction whose own SSA result *is* the value to
+            // forward to, so this slots directly into the same `cached_loads` lookup used for
+            // intra-block redundant-load elimination below.
+            if let Some(entry_set) = available.available_loads_entering(block_id) {
+                for idx in entry_set.iter() {
+                    let earlier_load = InsnId(idx);
+                    if let Some(loc) = tracker.get_location(earlier_load) {
+                        cached_loads.entry(loc.clone()).or_insert(earlier_load);
+                    }
+                }
+            }
+
+            let insns: Vec<InsnId> = func.block(block_id).insns().copied().collect();
+
+            for insn_id in insns {
+                let insn = func.find(insn_id);
+                let Some(loc) = tracker.get_location(insn_id).cloned() else { continue };
+
+                if let Some(value) = Self::stored_value(&insn) {
+                    last_value.retain(|cached_loc, _| !cached_loc.may_alias(&loc));
+                    cached_loads.retain(|cached_loc, _| !cached_loc.may_alias(&loc));
+                    last_value.insert(loc, value);
+                } else if Self::is_load(&insn) {
+                    if let Some(&forwarded) = last_value.get(&loc) {
+                        func.make_equal_to(insn_id, forwarded);
+                    } else if let Some(&earlier_load) = cached_loads.get(&loc) {
+                        func.make_equal_to(insn_id, earlier_load);
+                    } else {
+                        cached_loads.insert(loc, insn_id);
+                    }
+                }
+            }
+        }
+    }
 }
 
+/// Forward CFG dataflow that computes, at every load instruction, whether that load's result is
+/// still valid (not clobbered by an intervening may-aliasing store) on *every* path reaching it.
+/// This generalizes the per-block scan in [`MemoryOpTracker::forward_and_eliminate`] across block
+/// boundaries, so a load that's redundant with one from a predecessor block can be eliminated too.
+///
+/// Each load instruction owns one bit in a [`BitSet<InsnId>`] sized to the function's total
+/// instruction count. The transfer function for a block starts from the meet (intersection) of
+/// its predecessors' out-sets -- a load is available only if it's available on *every* incoming
+/// path -- and then, walking the block in order, sets the bit for each load it defines and clears
+/// the bit of every previously-available load whose location may-alias a store's location.
+/// [`BitSet::intersect_with`] is exactly this meet operator, so the fixpoint loop over
+/// `func.rpo()` just keeps re-applying block transfer functions and intersecting into each
+/// successor's in-set until nothing changes (needed because back-edges mean a block's in-set can
+/// depend on a not-yet-processed successor).
+pub struct AvailableLoads {
+    /// Available-load set immediately after each instruction.
+    after: HashMap<InsnId, BitSet<InsnId>>,
+    /// Available-load set on entry to each block, i.e. the meet of its predecessors' out-sets.
+    /// This is what [`MemoryOpTracker::forward_and_eliminate`] consults to forward a load across a
+    /// block boundary, not just within the block that defines it.
+    entering: HashMap<BlockId, BitSet<InsnId>>,
+}
+
+impl AvailableLoads {
+    /// Run the dataflow to a fixpoint and record the available-load set after every instruction.
+    pub fn compute(func: &Function, tracker: &MemoryOpTracker) -> Self {
+        let num_insns = func.num_insns();
+        let blocks: Vec<BlockId> = func.rpo().collect();
+        // Seed every block's out-set at the lattice top before iterating. For an intersection
+        // meet, top is the *universal* set (every bit set), not empty: a block reached only via a
+        // not-yet-processed back-edge would otherwise intersect with an empty placeholder on the
+        // first pass and get stuck at the trivial all-zero fixpoint forever, so no load inside a
+        // loop could ever be proven available. The entry block(s) (no predecessors) are the
+        // exception -- nothing flows into them, so they start empty, same as any other
+        // available-expressions analysis.
+        let mut block_out: HashMap<BlockId, BitSet<InsnId>> = HashMap::new();
+        for &block_id in &blocks {
+            let is_entry = func.block(block_id).preds().next().is_none();
+            let initial = if is_entry { BitSet::with_capacity(num_insns) } else { Self::full_bitset(num_insns) };
+            block_out.insert(block_id, initial);
+        }
+
+        // Iterate to a fixpoint: a block's in-set is the meet (intersection) of its predecessors'
+        // out-sets, so back-edges mean we may need several passes before nothing changes.
+        let mut after: HashMap<InsnId, BitSet<InsnId>> = HashMap::new();
+        let mut entering: HashMap<BlockId, BitSet<InsnId>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block_id in &blocks {
+                let preds: Vec<BlockId> = func.block(block_id).preds().collect();
+                let mut cur = match preds.first() {
+                    Some(&first) => block_out[&first].clone(),
+                    None => BitSet::with_capacity(num_insns),
+                };
+                for &pred in preds.iter().skip(1) {
+                    cur.intersect_with(&block_out[&pred]);
+                }
+                entering.insert(block_id, cur.clone());
+
+                for &insn_id in func.block(block_id).insns() {
+                    let insn = func.find(insn_id);
+                    if MemoryOpTracker::is_load(&insn) {
+                        cur.insert(insn_id);
+                    } else if let Some(loc) = tracker.get_location(insn_id) {
+                        if MemoryOpTracker::stored_value(&insn).is_some() {
+                            // Kill every currently-available load whose location may-alias this
+                            // store, conservatively.
+                            Self::kill_aliasing(&mut cur, loc, tracker);
+                        }
+                    }
+                    after.insert(insn_id, cur.clone());
+                }
+
+                if block_out[&block_id] != cur {
+                    changed = true;
+                }
+                block_out.insert(block_id, cur);
+            }
+        }
+
+        AvailableLoads { after, entering }
+    }
+
+    /// Available-load set immediately after `insn_id`, if it was recorded.
+    pub fn available_loads_at(&self, insn_id: InsnId) -> Option<&BitSet<InsnId>> {
+        self.after.get(&insn_id)
+    }
+
+    /// Available-load set on entry to `block_id`, if it was recorded.
+    pub fn available_loads_entering(&self, block_id: BlockId) -> Option<&BitSet<InsnId>> {
+        self.entering.get(&block_id)
+    }
+
+    /// The lattice top for an intersection meet: every bit set.
+    fn full_bitset(num_insns: usize) -> BitSet<InsnId> {
+        let mut dst = BitSet::with_capacity(num_insns);
+        for i in 0..num_insns {
+            dst.insert(InsnId(i));
+        }
+        dst
+    }
+
+    /// Clear the bit of every available load whose location may-alias `store_loc`, in place.
+    fn kill_aliasing(set: &mut BitSet<InsnId>, store_loc: &MemoryLocation, tracker: &MemoryOpTracker) {
+        for idx in set.iter().collect::<Vec<_>>() {
+            let id = InsnId(idx);
+            if tracker.get_location(id).is_some_and(|loc| loc.may_alias(store_loc)) {
+                set.remove(id);
+            }
+        }
+    }
+}
+
+// TODO(max): `MemoryOpTracker::forward_and_eliminate` and `AvailableLoads::compute` -- the two
+// most behaviorally significant passes in this file -- have no test coverage below. Only the pure
+// data structures they're built on (`MemoryLocation::may_alias`, `BitSet`) are tested. Both take a
+// `&Function`/`&mut Function`, and this tree has no `Function`/`BlockId` test-construction helper
+// to drive them with, so there's nothing concrete to assert against here yet. Once `hir.rs` lands
+// with a way to build a `Function` in a test, add at least:
+//   - a straight-line block exercising store-to-load forwarding and redundant-load elimination
+//   - a loop (self-loop or back-edge) carrying a load that's available on every iteration, to
+//     regression-test the lattice-top fix in `AvailableLoads::compute`
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,6 +560,21 @@ mod tests {
         assert!(unknown.may_alias(&array));
     }
 
+    #[test]
+    fn test_alias_class_immutable_never_aliases_anything() {
+        let immutable = AliasClass::Immutable;
+        assert!(!immutable.may_alias(&AliasClass::ArrayIvar));
+        assert!(!AliasClass::ArrayIvar.may_alias(&immutable));
+        // Not even another read-only location, nor Unknown: nothing ever stores to frozen memory.
+        assert!(!immutable.may_alias(&AliasClass::Immutable));
+        assert!(!immutable.may_alias(&AliasClass::Unknown));
+    }
+
+    #[test]
+    fn test_from_ivar_type_symbol_is_immutable() {
+        assert_eq!(AliasClass::from_ivar_type(&types::Symbol), AliasClass::Immutable);
+    }
+
     #[test]
     fn test_memory_location_same_object_same_ivar() {
         let obj = InsnId(0);
@@ -316,6 +631,93 @@ mod tests {
         assert!(!loc1.may_alias(&loc4));
     }
 
+    #[test]
+    fn test_array_element_distinct_constant_indices_same_array_do_not_alias() {
+        let arr = InsnId(0);
+        let loc0 = MemoryLocation::ArrayElement(arr, Some(0), AliasClass::ArrayElem);
+        let loc1 = MemoryLocation::ArrayElement(arr, Some(1), AliasClass::ArrayElem);
+        assert!(!loc0.may_alias(&loc1));
+    }
+
+    #[test]
+    fn test_array_element_same_constant_index_aliases() {
+        let arr = InsnId(0);
+        let loc0 = MemoryLocation::ArrayElement(arr, Some(0), AliasClass::ArrayElem);
+        let loc0_again = MemoryLocation::ArrayElement(arr, Some(0), AliasClass::ArrayElem);
+        assert!(loc0.may_alias(&loc0_again));
+    }
+
+    #[test]
+    fn test_array_element_unknown_index_aliases() {
+        let arr = InsnId(0);
+        let known = MemoryLocation::ArrayElement(arr, Some(0), AliasClass::ArrayElem);
+        let unknown = MemoryLocation::ArrayElement(arr, None, AliasClass::ArrayElem);
+        assert!(known.may_alias(&unknown));
+    }
+
+    #[test]
+    fn test_array_element_negative_index_conservatively_aliases() {
+        let arr = InsnId(0);
+        // `a[-1]` and `a[3]` could be the same element depending on `a`'s length, which isn't
+        // tracked here, so a negative index must never be treated as provably distinct.
+        let negative = MemoryLocation::ArrayElement(arr, Some(-1), AliasClass::ArrayElem);
+        let positive = MemoryLocation::ArrayElement(arr, Some(3), AliasClass::ArrayElem);
+        assert!(negative.may_alias(&positive));
+        // Nor do two distinct negative indices, for the same reason.
+        let other_negative = MemoryLocation::ArrayElement(arr, Some(-2), AliasClass::ArrayElem);
+        assert!(negative.may_alias(&other_negative));
+    }
+
+    #[test]
+    fn test_array_element_different_arrays_fall_back_to_alias_class() {
+        let arr1 = InsnId(0);
+        let arr2 = InsnId(1);
+        let loc1 = MemoryLocation::ArrayElement(arr1, Some(0), AliasClass::ArrayElem);
+        let loc2 = MemoryLocation::ArrayElement(arr2, Some(0), AliasClass::ArrayElem);
+        // Different arrays, but same alias class: conservatively may alias.
+        assert!(loc1.may_alias(&loc2));
+    }
+
+    #[test]
+    fn test_hash_element_distinct_literal_keys_same_hash_do_not_alias() {
+        let hash = InsnId(0);
+        let loc1 = MemoryLocation::HashElement(hash, Some(HashKey::Symbol(1)));
+        let loc2 = MemoryLocation::HashElement(hash, Some(HashKey::Symbol(2)));
+        assert!(!loc1.may_alias(&loc2));
+    }
+
+    #[test]
+    fn test_hash_element_equal_literal_keys_alias() {
+        let hash = InsnId(0);
+        let loc1 = MemoryLocation::HashElement(hash, Some(HashKey::Fixnum(5)));
+        let loc2 = MemoryLocation::HashElement(hash, Some(HashKey::Fixnum(5)));
+        assert!(loc1.may_alias(&loc2));
+    }
+
+    #[test]
+    fn test_hash_element_non_constant_key_aliases() {
+        let hash = InsnId(0);
+        let known = MemoryLocation::HashElement(hash, Some(HashKey::FrozenString("a".to_string())));
+        let unknown = MemoryLocation::HashElement(hash, None);
+        assert!(known.may_alias(&unknown));
+    }
+
+    #[test]
+    fn test_full_bitset_is_identity_for_intersection() {
+        // This is the lattice-top property the loop-carried-load fix in `AvailableLoads::compute`
+        // depends on: seeding a not-yet-visited block's out-set with the universal set must leave
+        // any real predecessor's out-set unchanged when intersected against it, so a load that's
+        // genuinely available on entry to a loop isn't spuriously killed by the placeholder value
+        // on the first fixpoint pass (as an empty placeholder would do, since anything intersected
+        // with empty is empty).
+        let mut some_set: BitSet<InsnId> = BitSet::with_capacity(8);
+        some_set.insert(InsnId(2));
+        some_set.insert(InsnId(5));
+        let mut intersected = some_set.clone();
+        intersected.intersect_with(&AvailableLoads::full_bitset(8));
+        assert_eq!(intersected, some_set);
+    }
+
     #[test]
     fn test_cross_type_no_alias() {
         let ivar = MemoryLocation::InstanceVariable(InsnId(0), 1, AliasClass::ArrayIvar);